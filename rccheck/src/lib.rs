@@ -1,24 +1,39 @@
 // Copyright(C) 2022, Mysten Labs
 // SPDX-License-Identifier: Apache-2.0
-use std::{fmt, time::SystemTime};
+use std::fmt;
+use std::sync::Arc;
 
-use rustls::{
-    client::{ServerCertVerified, ServerCertVerifier},
-    server::{ClientCertVerified, ClientCertVerifier},
-};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{CertificateError, DigitallySignedStruct, DistinguishedName, OtherError, SignatureScheme};
 use serde::{
     de::{Error, Visitor},
     Deserialize, Deserializer, Serialize,
 };
+use sha2::{Digest, Sha256};
 use x509_parser::certificate::X509Certificate;
+use x509_parser::revocation_list::CertificateRevocationList;
 use x509_parser::{traits::FromDer, x509::SubjectPublicKeyInfo};
 
 #[cfg(test)]
 #[path = "tests/psk.rs"]
 pub mod psk;
 
-type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
-static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256, &webpki::ED25519];
+/// The default [`CryptoProvider`] used when a verifier is constructed without an explicit
+/// one, so existing callers don't need to thread one through by hand.
+fn default_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// The signature algorithms a `provider` is willing to verify handshake signatures and
+/// certificate chains with.
+fn supported_algs(
+    provider: &CryptoProvider,
+) -> &'static [&'static dyn webpki::SignatureVerificationAlgorithm] {
+    provider.signature_verification_algorithms.all
+}
 
 /// X.509 `SubjectPublicKeyInfo` (SPKI) as defined in [RFC 5280 Section 4.1.2.7].
 ///
@@ -33,10 +48,40 @@ static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256, &
 ///
 /// [RFC 5280 Section 4.1.2.7]: https://tools.ietf.org/html/rfc5280#section-4.1.2.7
 ///
-/// We only support ECDSA P-256 & Ed25519 (for now).
+/// We support every signature algorithm the configured [`CryptoProvider`] enables, which
+/// by default is the full rustls default set: ECDSA (P-256/P-384, SHA-256/SHA-384),
+/// Ed25519, and RSA (PSS and PKCS#1).
+#[derive(Clone, Debug)]
+pub struct Psk<'a> {
+    pub spki: SubjectPublicKeyInfo<'a>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl<'a> Psk<'a> {
+    /// Pins `spki`, verifying handshake signatures and certificate chains with the
+    /// algorithms enabled by `provider`.
+    pub fn new(spki: SubjectPublicKeyInfo<'a>, provider: Arc<CryptoProvider>) -> Self {
+        Self { spki, provider }
+    }
+
+    /// Pins `spki`, verifying with the default (`ring`) [`CryptoProvider`].
+    pub fn with_default_provider(spki: SubjectPublicKeyInfo<'a>) -> Self {
+        Self::new(spki, default_provider())
+    }
+
+    /// Switches to [`RawPublicKey`] mode: the peer is authenticated solely by proving
+    /// possession of this pinned key over the handshake signature, without parsing or
+    /// validating any X.509 certificate.
+    pub fn into_raw_public_key(self) -> RawPublicKey<'a> {
+        RawPublicKey(self)
+    }
+}
 
-#[derive(PartialEq, Clone, Debug)]
-pub struct Psk<'a>(pub SubjectPublicKeyInfo<'a>);
+impl<'a> PartialEq for Psk<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.spki == other.spki
+    }
+}
 
 impl<'a> Eq for Psk<'a> {}
 
@@ -49,7 +94,7 @@ impl<'a> Serialize for Psk<'a> {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_bytes(self.0.raw)
+        serializer.serialize_bytes(self.spki.raw)
     }
 }
 
@@ -67,7 +112,7 @@ impl<'de> Visitor<'de> for DerBytesVisitor {
         E: Error,
     {
         let (_, spki) = SubjectPublicKeyInfo::from_der(v).map_err(Error::custom)?;
-        Ok(Psk(spki))
+        Ok(Psk::with_default_provider(spki))
     }
 
     fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
@@ -75,7 +120,7 @@ impl<'de> Visitor<'de> for DerBytesVisitor {
         E: Error,
     {
         let (_, spki) = SubjectPublicKeyInfo::from_der(v.as_bytes()).map_err(Error::custom)?;
-        Ok(Psk(spki))
+        Ok(Psk::with_default_provider(spki))
     }
 }
 
@@ -99,134 +144,1083 @@ impl<'a> ClientCertVerifier for Psk<'a> {
         true
     }
 
-    fn client_auth_mandatory(&self) -> Option<bool> {
-        Some(true)
+    fn client_auth_mandatory(&self) -> bool {
+        true
     }
 
-    fn client_auth_root_subjects(&self) -> Option<rustls::DistinguishedNames> {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
         // We can't guarantee subjects before having seen the cert
-        None
+        &[]
     }
 
     fn verify_client_cert(
         &self,
-        end_entity: &rustls::Certificate,
-        intermediates: &[rustls::Certificate],
-        now: SystemTime,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
     ) -> Result<ClientCertVerified, rustls::Error> {
         // Check this matches the key we expect
-        let cert = X509Certificate::from_der(&end_entity.0[..])
-            .map_err(|_| rustls::Error::InvalidCertificateEncoding)?;
+        let cert = X509Certificate::from_der(end_entity.as_ref())
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
         let spki = cert.1.public_key().clone();
-        if spki != self.0 {
-            return Err(rustls::Error::InvalidCertificateData(format!(
+        if spki != self.spki {
+            return Err(other_error(format!(
                 "invalid peer certificate: received {:?} instead of expected {:?}",
-                spki, self.0
+                spki, self.spki
             )));
         }
 
         // We now check we're receiving correctly signed data with the expected key
         let (cert, chain, trustroots) = prepare_for_self_signed(end_entity, intermediates)?;
-        let now = webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
-        cert.verify_is_valid_tls_client_cert(
-            SUPPORTED_SIG_ALGS,
-            &webpki::TlsClientTrustAnchors(&trustroots),
-            &chain,
+        cert.verify_for_usage(
+            supported_algs(&self.provider),
+            &trustroots,
+            chain,
             now,
+            webpki::KeyUsage::client_auth(),
+            None,
         )
         .map_err(pki_error)
         .map(|_| ClientCertVerified::assertion())
     }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
 }
 
 impl<'a> ServerCertVerifier for Psk<'a> {
     fn verify_server_cert(
         &self,
-        end_entity: &rustls::Certificate,
-        intermediates: &[rustls::Certificate],
-        server_name: &rustls::ServerName,
-        scts: &mut dyn Iterator<Item = &[u8]>,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
         ocsp_response: &[u8],
-        now: std::time::SystemTime,
-    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
         // Check this matches the key we expect
-        let cert = X509Certificate::from_der(&end_entity.0[..])
-            .map_err(|_| rustls::Error::InvalidCertificateEncoding)?;
+        let cert = X509Certificate::from_der(end_entity.as_ref())
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
         let spki = cert.1.public_key().clone();
-        if spki != self.0 {
-            return Err(rustls::Error::InvalidCertificateData(format!(
+        if spki != self.spki {
+            return Err(other_error(format!(
                 "invalid peer certificate: received {:?} instead of expected {:?}",
-                spki, self.0
+                spki, self.spki
             )));
         }
 
         // Then we check this is actually a valid self-signed certificate with matching name
         let (cert, chain, trustroots) = prepare_for_self_signed(end_entity, intermediates)?;
-        let webpki_now =
-            webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
 
-        let dns_nameref = match server_name {
-            rustls::ServerName::DnsName(dns_name) => {
-                webpki::DnsNameRef::try_from_ascii_str(dns_name.as_ref())
-                    .map_err(|_| rustls::Error::UnsupportedNameType)?
-            }
-            _ => return Err(rustls::Error::UnsupportedNameType),
-        };
+        cert.verify_for_usage(
+            supported_algs(&self.provider),
+            &trustroots,
+            chain,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+        )
+        .map_err(pki_error)?;
+
+        if !ocsp_response.is_empty() {
+            tracing::trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+        }
 
-        let cert = cert
-            .verify_is_valid_tls_server_cert(
-                SUPPORTED_SIG_ALGS,
-                &webpki::TlsServerTrustAnchors(&trustroots),
-                &chain,
-                webpki_now,
-            )
+        cert.verify_is_valid_for_subject_name(server_name)
             .map_err(pki_error)
-            .map(|_| cert)?;
+            .map(|_| ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// A set of pinned public keys, any of which is accepted as a peer's identity.
+///
+/// This supports zero-downtime key rotation: during a rotation window a node can publish
+/// both its old and new [`SubjectPublicKeyInfo`], and peers that still pin the old key
+/// keep working until they pick up the new one.
+#[derive(Clone, Debug)]
+pub struct PskSet<'a> {
+    pub spkis: Vec<SubjectPublicKeyInfo<'a>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl<'a> PskSet<'a> {
+    pub fn new(spkis: Vec<SubjectPublicKeyInfo<'a>>, provider: Arc<CryptoProvider>) -> Self {
+        Self { spkis, provider }
+    }
+
+    pub fn with_default_provider(spkis: Vec<SubjectPublicKeyInfo<'a>>) -> Self {
+        Self::new(spkis, default_provider())
+    }
+}
+
+impl<'a> PartialEq for PskSet<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.spkis == other.spkis
+    }
+}
+
+impl<'a> Eq for PskSet<'a> {}
+
+struct SpkiBytes<'a>(&'a [u8]);
+
+impl<'a> Serialize for SpkiBytes<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl<'a> Serialize for PskSet<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let spkis: Vec<SpkiBytes> = self.spkis.iter().map(|spki| SpkiBytes(spki.raw)).collect();
+        spkis.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PskSet<'de> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let psks = Vec::<Psk<'de>>::deserialize(deserializer)?;
+        Ok(PskSet::with_default_provider(
+            psks.into_iter().map(|psk| psk.spki).collect(),
+        ))
+    }
+}
+
+/// A `ClientCertVerifier` that accepts a client certificate whose public key matches any
+/// key in the pinned set, without any name checking.
+impl<'a> ClientCertVerifier for PskSet<'a> {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        true
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        // We can't guarantee subjects before having seen the cert
+        &[]
+    }
 
-        let mut peekable = scts.peekable();
-        if peekable.peek().is_none() {
-            tracing::trace!("Met unvalidated certificate transparency data");
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        // Check this matches one of the keys we expect
+        let cert = X509Certificate::from_der(end_entity.as_ref())
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let spki = cert.1.public_key().clone();
+        if !self.spkis.iter().any(|expected| *expected == spki) {
+            return Err(other_error(format!(
+                "invalid peer certificate: received {:?}, which matches none of the pinned keys",
+                spki
+            )));
         }
 
+        // We now check we're receiving correctly signed data with the matched key
+        let (cert, chain, trustroots) = prepare_for_self_signed(end_entity, intermediates)?;
+        cert.verify_for_usage(
+            supported_algs(&self.provider),
+            &trustroots,
+            chain,
+            now,
+            webpki::KeyUsage::client_auth(),
+            None,
+        )
+        .map_err(pki_error)
+        .map(|_| ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl<'a> ServerCertVerifier for PskSet<'a> {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // Check this matches one of the keys we expect
+        let cert = X509Certificate::from_der(end_entity.as_ref())
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let spki = cert.1.public_key().clone();
+        if !self.spkis.iter().any(|expected| *expected == spki) {
+            return Err(other_error(format!(
+                "invalid peer certificate: received {:?}, which matches none of the pinned keys",
+                spki
+            )));
+        }
+
+        // Then we check this is actually a valid self-signed certificate with matching name
+        let (cert, chain, trustroots) = prepare_for_self_signed(end_entity, intermediates)?;
+
+        cert.verify_for_usage(
+            supported_algs(&self.provider),
+            &trustroots,
+            chain,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+        )
+        .map_err(pki_error)?;
+
         if !ocsp_response.is_empty() {
             tracing::trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
         }
 
-        cert.verify_is_valid_for_dns_name(dns_nameref)
+        cert.verify_is_valid_for_subject_name(server_name)
             .map_err(pki_error)
             .map(|_| ServerCertVerified::assertion())
     }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// A peer public key pinned by the SHA-256 fingerprint of its DER-encoded
+/// `SubjectPublicKeyInfo`, rather than the full SPKI itself.
+///
+/// Operators often distribute a key identity as a compact hex/base64 digest instead of
+/// shipping the full DER blob; this keeps config files small while still running the
+/// same self-signed chain validation as [`Psk`] once the fingerprint matches.
+#[derive(Clone, Debug)]
+pub struct Fingerprint {
+    pub digest: [u8; 32],
+    provider: Arc<CryptoProvider>,
+}
+
+impl Fingerprint {
+    pub fn new(digest: [u8; 32], provider: Arc<CryptoProvider>) -> Self {
+        Self { digest, provider }
+    }
+
+    pub fn with_default_provider(digest: [u8; 32]) -> Self {
+        Self::new(digest, default_provider())
+    }
+
+    /// Computes the fingerprint of a `SubjectPublicKeyInfo`'s DER encoding.
+    pub fn of(spki: &SubjectPublicKeyInfo) -> [u8; 32] {
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&Sha256::digest(spki.raw));
+        digest
+    }
 }
 
-type CertChainAndRoots<'a> = (
-    webpki::EndEntityCert<'a>,
-    Vec<&'a [u8]>,
-    Vec<webpki::TrustAnchor<'a>>,
-);
+impl PartialEq for Fingerprint {
+    fn eq(&self, other: &Self) -> bool {
+        self.digest == other.digest
+    }
+}
+
+impl Eq for Fingerprint {}
+
+/// A `ClientCertVerifier` that will ensure that every client provides a valid certificate
+/// whose public key matches the pinned fingerprint, without any name checking.
+impl ClientCertVerifier for Fingerprint {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        true
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        // We can't guarantee subjects before having seen the cert
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        // Check this matches the fingerprint we expect
+        let cert = X509Certificate::from_der(end_entity.as_ref())
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let spki = cert.1.public_key();
+        if !constant_time_eq(&Fingerprint::of(spki), &self.digest) {
+            return Err(other_error(
+                "invalid peer certificate: public key fingerprint does not match the pinned fingerprint",
+            ));
+        }
+
+        // We now check we're receiving correctly signed data with the expected key
+        let (cert, chain, trustroots) = prepare_for_self_signed(end_entity, intermediates)?;
+        cert.verify_for_usage(
+            supported_algs(&self.provider),
+            &trustroots,
+            chain,
+            now,
+            webpki::KeyUsage::client_auth(),
+            None,
+        )
+        .map_err(pki_error)
+        .map(|_| ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl ServerCertVerifier for Fingerprint {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // Check this matches the fingerprint we expect
+        let cert = X509Certificate::from_der(end_entity.as_ref())
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let spki = cert.1.public_key();
+        if !constant_time_eq(&Fingerprint::of(spki), &self.digest) {
+            return Err(other_error(
+                "invalid peer certificate: public key fingerprint does not match the pinned fingerprint",
+            ));
+        }
+
+        // Then we check this is actually a valid self-signed certificate with matching name
+        let (cert, chain, trustroots) = prepare_for_self_signed(end_entity, intermediates)?;
+
+        cert.verify_for_usage(
+            supported_algs(&self.provider),
+            &trustroots,
+            chain,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+        )
+        .map_err(pki_error)?;
+
+        if !ocsp_response.is_empty() {
+            tracing::trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+        }
+
+        cert.verify_is_valid_for_subject_name(server_name)
+            .map_err(pki_error)
+            .map(|_| ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Compares two byte slices in constant time, to avoid leaking fingerprint bytes through
+/// a timing side-channel.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Authenticates a peer solely by its handshake signature, in the style of RFC 7250 raw
+/// public keys.
+///
+/// Unlike [`Psk`], which pins a key but still parses and validates a self-signed X.509
+/// certificate chain around it, `RawPublicKey` never looks at the certificate message at
+/// all: the end-entity bytes are accepted unconditionally, and the peer is authenticated
+/// only by proving possession of the pinned key over the TLS handshake transcript. This
+/// closes a gap in cert-based pinning, where the chain check proves the key appears in a
+/// self-signed cert but nothing proves the peer actually holds that key until the
+/// handshake signature is checked against it directly.
+#[derive(Clone, Debug)]
+pub struct RawPublicKey<'a>(pub Psk<'a>);
+
+impl<'a> RawPublicKey<'a> {
+    fn verify_handshake_signature(
+        &self,
+        message: &[u8],
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algs = self
+            .0
+            .provider
+            .signature_verification_algorithms
+            .mapping
+            .iter()
+            .find(|(scheme, _)| *scheme == dss.scheme())
+            .map(|(_, algs)| *algs)
+            .ok_or(rustls::Error::PeerIncompatible(
+                rustls::PeerIncompatible::NoSignatureSchemesInCommon,
+            ))?;
+
+        let public_key = self.0.spki.subject_public_key.data.as_ref();
+        let signature = dss.signature();
+
+        if algs
+            .iter()
+            .any(|alg| alg.verify_signature(public_key, message, signature).is_ok())
+        {
+            Ok(HandshakeSignatureValid::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(CertificateError::BadSignature))
+        }
+    }
+}
+
+impl<'a> ClientCertVerifier for RawPublicKey<'a> {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        true
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        // No certificate to check: possession of the pinned key is proven below, by
+        // `verify_tls12_signature`/`verify_tls13_signature`.
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        _cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_handshake_signature(message, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        _cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_handshake_signature(message, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0
+            .provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl<'a> ServerCertVerifier for RawPublicKey<'a> {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // No certificate to check: possession of the pinned key is proven below, by
+        // `verify_tls12_signature`/`verify_tls13_signature`.
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        _cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_handshake_signature(message, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        _cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_handshake_signature(message, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0
+            .provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Which trust model a [`Verifier`] should apply to an incoming certificate.
+#[derive(Clone, Debug)]
+pub enum CertificateMode<'a> {
+    /// Pin the peer's certificate by its public key, reinterpreting the self-signed
+    /// end-entity certificate as its own trust root (today's behavior, see [`Psk`]).
+    SelfSigned(Psk<'a>),
+    /// Validate the peer's certificate chain against a configured set of trust anchors,
+    /// as issued by a real certificate authority.
+    AuthorityBased(rustls::RootCertStore),
+}
+
+/// A `ServerCertVerifier`/`ClientCertVerifier` that validates the peer certificate
+/// according to its [`CertificateMode`].
+///
+/// In [`CertificateMode::AuthorityBased`] mode, a certificate that carries no Subject
+/// Alternative Name extension is matched against its Subject Common Name instead, since
+/// some CA-issued certificates predate the SAN requirement.
+#[derive(Clone, Debug)]
+pub struct Verifier<'a> {
+    pub mode: CertificateMode<'a>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl<'a> Verifier<'a> {
+    pub fn new(mode: CertificateMode<'a>, provider: Arc<CryptoProvider>) -> Self {
+        Self { mode, provider }
+    }
+
+    pub fn with_default_provider(mode: CertificateMode<'a>) -> Self {
+        Self::new(mode, default_provider())
+    }
+}
+
+impl<'a> ClientCertVerifier for Verifier<'a> {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        true
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        // We can't guarantee subjects before having seen the cert
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        match &self.mode {
+            CertificateMode::SelfSigned(psk) => {
+                psk.verify_client_cert(end_entity, intermediates, now)
+            }
+            CertificateMode::AuthorityBased(roots) => {
+                let (cert, chain, trustroots) =
+                    prepare_for_authority(end_entity, intermediates, roots)?;
+                cert.verify_for_usage(
+                    supported_algs(&self.provider),
+                    &trustroots,
+                    chain,
+                    now,
+                    webpki::KeyUsage::client_auth(),
+                    None,
+                )
+                .map_err(pki_error)
+                .map(|_| ClientCertVerified::assertion())
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl<'a> ServerCertVerifier for Verifier<'a> {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let roots = match &self.mode {
+            CertificateMode::SelfSigned(psk) => {
+                return psk.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+            }
+            CertificateMode::AuthorityBased(roots) => roots,
+        };
+
+        let (cert, chain, trustroots) = prepare_for_authority(end_entity, intermediates, roots)?;
+
+        cert.verify_for_usage(
+            supported_algs(&self.provider),
+            &trustroots,
+            chain,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+        )
+        .map_err(pki_error)?;
+
+        if !ocsp_response.is_empty() {
+            tracing::trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+        }
+
+        verify_dns_name_or_common_name(end_entity, &cert, server_name)
+            .map(|_| ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// How to treat a client certificate whose issuer has no CRL configured on a
+/// [`CrlClientCertVerifier`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RevocationPolicy {
+    /// Only reject the certificate if a CRL from its issuer is present and lists it.
+    RejectIfListed,
+    /// Require a CRL on file for the end-entity certificate's issuer; a certificate whose
+    /// issuer has no matching CRL is itself a rejection. Intermediate certificates are not
+    /// checked against any CRL.
+    RequireCrlForEveryPath,
+}
+
+/// Wraps a `ClientCertVerifier` with certificate revocation list (CRL) enforcement.
+///
+/// Once the inner verifier accepts a client certificate on its key and chain, the
+/// end-entity's serial number is looked up in the CRL published by its issuer; a serial
+/// found on that list is rejected even though its key and chain are otherwise valid.
+///
+/// A CRL is only trusted once it is shown to be authentic: its signature must verify
+/// against one of `roots`, its issuer must match the anchor's subject, and it must fall
+/// within its `thisUpdate`/`nextUpdate` validity window at the time of the handshake.
+/// Without this, an attacker (or a broken, unauthenticated CRL-fetch pipeline) could
+/// substitute a forged or stale CRL that simply never lists the revoked serial.
+#[derive(Debug)]
+pub struct CrlClientCertVerifier<V> {
+    inner: V,
+    crls: Vec<CertificateRevocationList<'static>>,
+    policy: RevocationPolicy,
+    roots: rustls::RootCertStore,
+    provider: Arc<CryptoProvider>,
+}
+
+impl<V> CrlClientCertVerifier<V> {
+    /// Wraps `inner` with CRL enforcement, verifying CRLs with the default (`ring`)
+    /// [`CryptoProvider`].
+    pub fn new(
+        inner: V,
+        crls: Vec<CertificateRevocationList<'static>>,
+        policy: RevocationPolicy,
+        roots: rustls::RootCertStore,
+    ) -> Self {
+        Self::with_provider(inner, crls, policy, roots, default_provider())
+    }
+
+    pub fn with_provider(
+        inner: V,
+        crls: Vec<CertificateRevocationList<'static>>,
+        policy: RevocationPolicy,
+        roots: rustls::RootCertStore,
+        provider: Arc<CryptoProvider>,
+    ) -> Self {
+        Self {
+            inner,
+            crls,
+            policy,
+            roots,
+            provider,
+        }
+    }
+}
+
+/// Checks that `crl` is an authentic, currently-valid CRL from one of `roots`: its
+/// signature must verify against the matching trust anchor's public key, and `now` must
+/// fall within its `thisUpdate`/`nextUpdate` window.
+fn verify_crl_is_trustworthy(
+    crl: &CertificateRevocationList<'_>,
+    roots: &rustls::RootCertStore,
+    provider: &CryptoProvider,
+    now: UnixTime,
+) -> Result<(), rustls::Error> {
+    let anchor = roots
+        .roots
+        .iter()
+        .find(|anchor| anchor.subject.as_ref() == crl.issuer().as_raw())
+        .ok_or_else(|| other_error(format!("no trust anchor for CRL issuer {}", crl.issuer())))?;
+
+    let message = crl.tbs_cert_list.raw;
+    let signature = crl.signature_value.data.as_ref();
+    if !supported_algs(provider).iter().any(|alg| {
+        alg.verify_signature(anchor.subject_public_key_info.as_ref(), message, signature)
+            .is_ok()
+    }) {
+        return Err(other_error(
+            "CRL signature does not verify against its issuer's trust anchor",
+        ));
+    }
+
+    let now_secs = now.as_secs() as i64;
+    if crl.tbs_cert_list.this_update.timestamp() > now_secs {
+        return Err(other_error("CRL is not yet valid (thisUpdate is in the future)"));
+    }
+    match crl.tbs_cert_list.next_update {
+        Some(next_update) if next_update.timestamp() > now_secs => Ok(()),
+        Some(_) => Err(other_error("CRL has expired (past its nextUpdate)")),
+        None => Err(other_error(
+            "CRL has no nextUpdate and cannot be trusted indefinitely",
+        )),
+    }
+}
+
+impl<V: ClientCertVerifier> ClientCertVerifier for CrlClientCertVerifier<V> {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        self.inner.client_auth_mandatory()
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        self.inner.root_hint_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = self.inner.verify_client_cert(end_entity, intermediates, now)?;
+
+        let (_, cert) = X509Certificate::from_der(end_entity.as_ref())
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let serial = cert.raw_serial();
+        let issuer = cert.issuer();
+
+        match self.crls.iter().find(|crl| crl.issuer() == issuer) {
+            Some(crl) => {
+                verify_crl_is_trustworthy(crl, &self.roots, &self.provider, now)?;
+
+                if crl
+                    .iter_revoked_certificates()
+                    .any(|revoked| revoked.raw_serial() == serial)
+                {
+                    return Err(other_error(format!(
+                        "client certificate with serial {} has been revoked",
+                        cert.raw_serial_as_string()
+                    )));
+                }
+            }
+            None if self.policy == RevocationPolicy::RequireCrlForEveryPath => {
+                return Err(other_error(format!(
+                    "no CRL on file for issuer {}, and one is required",
+                    issuer
+                )));
+            }
+            None => {}
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+type CertChainAndRoot<'a> = (webpki::EndEntityCert<'a>, &'a [CertificateDer<'a>], Vec<webpki::TrustAnchor<'a>>);
 
 fn prepare_for_self_signed<'a>(
-    end_entity: &'a rustls::Certificate,
-    intermediates: &'a [rustls::Certificate],
-) -> Result<CertChainAndRoots<'a>, rustls::Error> {
+    end_entity: &'a CertificateDer<'a>,
+    intermediates: &'a [CertificateDer<'a>],
+) -> Result<CertChainAndRoot<'a>, rustls::Error> {
     // EE cert must appear first.
-    let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref()).map_err(pki_error)?;
-
-    let intermediates: Vec<&'a [u8]> = intermediates.iter().map(|cert| cert.0.as_ref()).collect();
+    let cert = webpki::EndEntityCert::try_from(end_entity).map_err(pki_error)?;
 
     // reinterpret the certificate as a root, materializing the self-signed policy
-    let root = webpki::TrustAnchor::try_from_cert_der(end_entity.0.as_ref()).map_err(pki_error)?;
+    let root = webpki::anchor_from_trusted_cert(end_entity).map_err(pki_error)?;
 
     Ok((cert, intermediates, vec![root]))
 }
 
+fn prepare_for_authority<'a>(
+    end_entity: &'a CertificateDer<'a>,
+    intermediates: &'a [CertificateDer<'a>],
+    roots: &'a rustls::RootCertStore,
+) -> Result<CertChainAndRoot<'a>, rustls::Error> {
+    let cert = webpki::EndEntityCert::try_from(end_entity).map_err(pki_error)?;
+    Ok((cert, intermediates, roots.roots.clone()))
+}
+
+/// Verifies `cert` is valid for `server_name`, falling back to a Subject Common Name
+/// comparison when the certificate has no Subject Alternative Name extension.
+fn verify_dns_name_or_common_name(
+    end_entity: &CertificateDer<'_>,
+    cert: &webpki::EndEntityCert,
+    server_name: &ServerName<'_>,
+) -> Result<(), rustls::Error> {
+    let (_, parsed) = X509Certificate::from_der(end_entity.as_ref())
+        .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+
+    let has_san = parsed.subject_alternative_name().ok().flatten().is_some();
+
+    if has_san {
+        return cert
+            .verify_is_valid_for_subject_name(server_name)
+            .map_err(pki_error);
+    }
+
+    let ServerName::DnsName(dns_name) = server_name else {
+        return Err(rustls::Error::UnsupportedNameType);
+    };
+
+    // No SAN: fall back to matching the Subject Common Name.
+    let common_name = parsed
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok());
+
+    match common_name {
+        Some(cn) if cn.eq_ignore_ascii_case(dns_name.as_ref()) => Ok(()),
+        _ => Err(other_error(format!(
+            "certificate is not valid for name {}",
+            dns_name.as_ref()
+        ))),
+    }
+}
+
+/// Wraps a plain message in a `rustls::Error` of kind `CertificateError::Other`, the
+/// closest 0.22 equivalent to the old free-form `InvalidCertificateData(String)`.
+fn other_error(message: impl Into<String>) -> rustls::Error {
+    #[derive(Debug)]
+    struct Message(String);
+
+    impl fmt::Display for Message {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(&self.0)
+        }
+    }
+
+    impl std::error::Error for Message {}
+
+    rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(Message(
+        message.into(),
+    )))))
+}
+
 fn pki_error(error: webpki::Error) -> rustls::Error {
     use webpki::Error::*;
     match error {
-        BadDer | BadDerTime => rustls::Error::InvalidCertificateEncoding,
-        InvalidSignatureForPublicKey => rustls::Error::InvalidCertificateSignature,
+        BadDer | BadDerTime => rustls::Error::InvalidCertificate(CertificateError::BadEncoding),
+        InvalidSignatureForPublicKey => {
+            rustls::Error::InvalidCertificate(CertificateError::BadSignature)
+        }
         UnsupportedSignatureAlgorithm | UnsupportedSignatureAlgorithmForPublicKey => {
-            rustls::Error::InvalidCertificateSignatureType
+            rustls::Error::InvalidCertificate(CertificateError::BadSignature)
         }
-        e => rustls::Error::InvalidCertificateData(format!("invalid peer certificate: {}", e)),
+        e => other_error(format!("invalid peer certificate: {}", e)),
     }
-}
\ No newline at end of file
+}
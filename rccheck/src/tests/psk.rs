@@ -0,0 +1,462 @@
+// Copyright(C) 2022, Mysten Labs
+// SPDX-License-Identifier: Apache-2.0
+use std::time::SystemTime;
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertificateRevocationListParams, DnType,
+    IsCa, KeyIdMethod, KeyPair, RevocationReason, RevokedCertParams, SerialNumber,
+};
+use rustls::client::danger::ServerCertVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use x509_parser::traits::FromDer;
+use x509_parser::x509::SubjectPublicKeyInfo;
+
+use super::{
+    CertificateMode, CrlClientCertVerifier, Fingerprint, Psk, PskSet, RevocationPolicy, Verifier,
+};
+
+/// Builds a self-signed certificate for `name`, returning it alongside its DER encoding.
+fn self_signed(name: &str) -> (Certificate, Vec<u8>) {
+    let params = CertificateParams::new(vec![name.to_string()]);
+    let cert = Certificate::from_params(params).expect("self-signed certificate");
+    let der = cert.serialize_der().expect("serialize self-signed cert");
+    (cert, der)
+}
+
+/// Extracts the `SubjectPublicKeyInfo` from a DER-encoded certificate.
+fn spki_of(der: &[u8]) -> SubjectPublicKeyInfo {
+    let (_, cert) = x509_parser::certificate::X509Certificate::from_der(der).expect("parse cert");
+    cert.public_key().clone()
+}
+
+fn unix_time_now() -> UnixTime {
+    UnixTime::now()
+}
+
+fn server_name(name: &str) -> ServerName<'static> {
+    ServerName::try_from(name.to_string()).expect("valid server name")
+}
+
+/// Builds a small CA and a leaf certificate it signs, with no Subject Alternative Name
+/// on the leaf (so tests can exercise the Subject Common Name fallback).
+fn ca_and_leaf(common_name: &str, serial: &[u8]) -> (Certificate, Vec<u8>, Vec<u8>) {
+    let mut ca_params = CertificateParams::new(vec![]);
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, "Test Root CA");
+    let ca_cert = Certificate::from_params(ca_params).expect("CA certificate");
+    let ca_der = ca_cert.serialize_der().expect("serialize CA cert");
+
+    let mut leaf_params = CertificateParams::new(vec![]);
+    leaf_params.serial_number = Some(SerialNumber::from_slice(serial));
+    leaf_params
+        .distinguished_name
+        .push(DnType::CommonName, common_name);
+    let leaf_cert = Certificate::from_params(leaf_params).expect("leaf certificate");
+    let leaf_der = leaf_cert
+        .serialize_der_with_signer(&ca_cert)
+        .expect("serialize CA-signed leaf cert");
+
+    (ca_cert, ca_der, leaf_der)
+}
+
+#[test]
+fn psk_accepts_certificate_with_matching_key() {
+    let (_cert, der) = self_signed("localhost");
+    let spki = spki_of(&der);
+    let psk = Psk::with_default_provider(spki);
+    let end_entity = CertificateDer::from(der);
+
+    psk.verify_server_cert(&end_entity, &[], &server_name("localhost"), &[], unix_time_now())
+        .expect("matching key and valid self-signed chain should be accepted");
+}
+
+#[test]
+fn psk_rejects_certificate_with_different_key() {
+    let (_cert, der) = self_signed("localhost");
+    let (_other_cert, other_der) = self_signed("localhost");
+    let pinned_spki = spki_of(&other_der);
+    let psk = Psk::with_default_provider(pinned_spki);
+    let end_entity = CertificateDer::from(der);
+
+    psk.verify_server_cert(&end_entity, &[], &server_name("localhost"), &[], unix_time_now())
+        .expect_err("a certificate signed by a different key must be rejected");
+}
+
+#[test]
+fn authority_based_verifier_accepts_ca_signed_leaf_via_common_name() {
+    let (_ca_cert, ca_der, leaf_der) = ca_and_leaf("leaf.example", &[0x01]);
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(ca_der))
+        .expect("CA cert should be a valid trust anchor");
+
+    let verifier = Verifier::with_default_provider(CertificateMode::AuthorityBased(roots));
+
+    verifier
+        .verify_server_cert(
+            &CertificateDer::from(leaf_der),
+            &[],
+            &server_name("leaf.example"),
+            &[],
+            unix_time_now(),
+        )
+        .expect("CA-signed leaf with no SAN should fall back to matching the Common Name");
+}
+
+#[test]
+fn authority_based_verifier_rejects_leaf_not_signed_by_a_trusted_root() {
+    let (_ca_cert, _ca_der, leaf_der) = ca_and_leaf("leaf.example", &[0x01]);
+    let (_other_ca_cert, other_ca_der, _other_leaf_der) = ca_and_leaf("leaf.example", &[0x02]);
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(other_ca_der))
+        .expect("CA cert should be a valid trust anchor");
+
+    let verifier = Verifier::with_default_provider(CertificateMode::AuthorityBased(roots));
+
+    verifier
+        .verify_server_cert(
+            &CertificateDer::from(leaf_der),
+            &[],
+            &server_name("leaf.example"),
+            &[],
+            unix_time_now(),
+        )
+        .expect_err("a leaf signed by an untrusted CA must be rejected");
+}
+
+#[test]
+fn authority_based_verifier_accepts_ca_signed_client_cert() {
+    let (_ca_cert, ca_der, leaf_der) = ca_and_leaf("client.example", &[0x03]);
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(ca_der))
+        .expect("CA cert should be a valid trust anchor");
+
+    let verifier = Verifier::with_default_provider(CertificateMode::AuthorityBased(roots));
+
+    verifier
+        .verify_client_cert(&CertificateDer::from(leaf_der), &[], unix_time_now())
+        .expect("CA-signed client cert should be accepted on the client-auth path too");
+}
+
+#[test]
+fn authority_based_verifier_rejects_client_cert_not_signed_by_a_trusted_root() {
+    let (_ca_cert, _ca_der, leaf_der) = ca_and_leaf("client.example", &[0x03]);
+    let (_other_ca_cert, other_ca_der, _other_leaf_der) = ca_and_leaf("client.example", &[0x04]);
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(other_ca_der))
+        .expect("CA cert should be a valid trust anchor");
+
+    let verifier = Verifier::with_default_provider(CertificateMode::AuthorityBased(roots));
+
+    verifier
+        .verify_client_cert(&CertificateDer::from(leaf_der), &[], unix_time_now())
+        .expect_err("a client cert signed by an untrusted CA must be rejected");
+}
+
+#[test]
+fn psk_set_accepts_either_pinned_key() {
+    let (_cert_a, der_a) = self_signed("a.example");
+    let (_cert_b, der_b) = self_signed("b.example");
+    let psk_set = PskSet::with_default_provider(vec![spki_of(&der_a), spki_of(&der_b)]);
+
+    psk_set
+        .verify_server_cert(&CertificateDer::from(der_a), &[], &server_name("a.example"), &[], unix_time_now())
+        .expect("first pinned key should be accepted");
+    psk_set
+        .verify_server_cert(&CertificateDer::from(der_b), &[], &server_name("b.example"), &[], unix_time_now())
+        .expect("second pinned key should be accepted");
+}
+
+#[test]
+fn psk_set_round_trips_through_serde() {
+    let (_cert_a, der_a) = self_signed("a.example");
+    let (_cert_b, der_b) = self_signed("b.example");
+    let psk_set = PskSet::with_default_provider(vec![spki_of(&der_a), spki_of(&der_b)]);
+
+    let encoded = bincode::serialize(&psk_set).expect("serialize PskSet");
+    let decoded: PskSet<'_> = bincode::deserialize(&encoded).expect("deserialize PskSet");
+
+    assert_eq!(decoded.spkis, psk_set.spkis);
+}
+
+#[test]
+fn psk_set_rejects_key_outside_the_set() {
+    let (_cert_a, der_a) = self_signed("a.example");
+    let (_cert_b, der_b) = self_signed("b.example");
+    let (_unpinned_cert, unpinned_der) = self_signed("a.example");
+    let psk_set = PskSet::with_default_provider(vec![spki_of(&der_a), spki_of(&der_b)]);
+
+    psk_set
+        .verify_server_cert(
+            &CertificateDer::from(unpinned_der),
+            &[],
+            &server_name("a.example"),
+            &[],
+            unix_time_now(),
+        )
+        .expect_err("a key outside the pinned set must be rejected");
+}
+
+#[test]
+fn fingerprint_accepts_certificate_with_matching_key() {
+    let (_cert, der) = self_signed("localhost");
+    let digest = Fingerprint::of(&spki_of(&der));
+    let fingerprint = Fingerprint::with_default_provider(digest);
+    let end_entity = CertificateDer::from(der);
+
+    fingerprint
+        .verify_server_cert(&end_entity, &[], &server_name("localhost"), &[], unix_time_now())
+        .expect("matching fingerprint should be accepted");
+}
+
+#[test]
+fn fingerprint_rejects_certificate_with_different_key() {
+    let (_cert, der) = self_signed("localhost");
+    let (_other_cert, other_der) = self_signed("localhost");
+    let digest = Fingerprint::of(&spki_of(&other_der));
+    let fingerprint = Fingerprint::with_default_provider(digest);
+    let end_entity = CertificateDer::from(der);
+
+    fingerprint
+        .verify_server_cert(&end_entity, &[], &server_name("localhost"), &[], unix_time_now())
+        .expect_err("a fingerprint mismatch must be rejected");
+}
+
+/// Signs `message` with `key_pair`'s private key, producing a `DigitallySignedStruct` in
+/// the shape a real TLS handshake would hand to `verify_tls13_signature`.
+fn sign_ecdsa_p256(key_pair: &KeyPair, message: &[u8]) -> DigitallySignedStruct {
+    let pkcs8 = key_pair.serialize_der();
+    let rng = ring::rand::SystemRandom::new();
+    let signing_key =
+        ring::signature::EcdsaKeyPair::from_pkcs8(&ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING, &pkcs8, &rng)
+            .expect("parse generated ECDSA key pair");
+    let signature = signing_key
+        .sign(&rng, message)
+        .expect("sign handshake transcript");
+    DigitallySignedStruct::new(SignatureScheme::ECDSA_NISTP256_SHA256, signature.as_ref().to_vec())
+}
+
+#[test]
+fn psk_verifies_a_genuine_tls13_handshake_signature() {
+    // Exercises the rustls 0.22 migration directly: Psk::verify_tls13_signature now
+    // delegates to rustls::crypto::verify_tls13_signature against the configured
+    // CryptoProvider, rather than the pre-0.22 webpki-based signature check.
+    let (cert, der) = self_signed("localhost");
+    let key_pair = cert.get_key_pair();
+    let spki = spki_of(&der);
+    let psk = Psk::with_default_provider(spki);
+    let end_entity = CertificateDer::from(der);
+
+    let message = b"tls 1.3, server CertificateVerify transcript";
+    let dss = sign_ecdsa_p256(key_pair, message);
+
+    psk.verify_tls13_signature(message, &end_entity, &dss)
+        .expect("a signature produced by the certificate's own key must verify");
+}
+
+#[test]
+fn raw_public_key_accepts_a_genuine_handshake_signature() {
+    let (cert, der) = self_signed("localhost");
+    let key_pair = cert.get_key_pair();
+    let spki = spki_of(&der);
+    let raw_public_key = Psk::with_default_provider(spki).into_raw_public_key();
+
+    let message = b"tls 1.3, server CertificateVerify transcript";
+    let dss = sign_ecdsa_p256(key_pair, message);
+
+    raw_public_key
+        .verify_tls13_signature(message, &CertificateDer::from(der), &dss)
+        .expect("a signature produced by the pinned key must verify");
+}
+
+#[test]
+fn raw_public_key_rejects_a_signature_from_the_wrong_key() {
+    let (_cert, der) = self_signed("localhost");
+    let (other_cert, _other_der) = self_signed("localhost");
+    let other_key_pair = other_cert.get_key_pair();
+    let spki = spki_of(&der);
+    let raw_public_key = Psk::with_default_provider(spki).into_raw_public_key();
+
+    let message = b"tls 1.3, server CertificateVerify transcript";
+    let dss = sign_ecdsa_p256(other_key_pair, message);
+
+    raw_public_key
+        .verify_tls13_signature(message, &CertificateDer::from(der), &dss)
+        .expect_err("a signature from a key other than the one pinned must be rejected");
+}
+
+#[test]
+fn raw_public_key_rejects_a_flipped_signature_byte() {
+    let (cert, der) = self_signed("localhost");
+    let key_pair = cert.get_key_pair();
+    let spki = spki_of(&der);
+    let raw_public_key = Psk::with_default_provider(spki).into_raw_public_key();
+
+    let message = b"tls 1.3, server CertificateVerify transcript";
+    let mut dss = sign_ecdsa_p256(key_pair, message);
+    let mut signature = dss.signature().to_vec();
+    signature[0] ^= 0xff;
+    dss = DigitallySignedStruct::new(SignatureScheme::ECDSA_NISTP256_SHA256, signature);
+
+    raw_public_key
+        .verify_tls13_signature(message, &CertificateDer::from(der), &dss)
+        .expect_err("a single flipped signature byte must be rejected");
+}
+
+#[test]
+fn crl_verifier_rejects_revoked_serial_on_an_authentic_crl() {
+    let serial = [0x2a];
+    let (ca_cert, ca_der, leaf_der) = ca_and_leaf("leaf.example", &serial);
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(ca_der))
+        .expect("CA cert should be a valid trust anchor");
+
+    let now = SystemTime::now();
+    let crl_params = CertificateRevocationListParams {
+        this_update: (now - std::time::Duration::from_secs(60)).into(),
+        next_update: (now + std::time::Duration::from_secs(3600)).into(),
+        crl_number: SerialNumber::from_slice(&[0x01]),
+        issuing_distribution_point: None,
+        revoked_certs: vec![RevokedCertParams {
+            serial_number: SerialNumber::from_slice(&serial),
+            revocation_time: now.into(),
+            reason_code: Some(RevocationReason::KeyCompromise),
+            invalidity_date: None,
+        }],
+        key_identifier_method: KeyIdMethod::Sha256,
+    };
+    let crl_der = crl_params
+        .serialize_der_with_signer(&ca_cert)
+        .expect("serialize CRL signed by the CA");
+    let (_, crl) = x509_parser::revocation_list::CertificateRevocationList::from_der(&crl_der)
+        .expect("parse generated CRL");
+
+    let inner = Verifier::with_default_provider(CertificateMode::AuthorityBased(roots.clone()));
+    let verifier =
+        CrlClientCertVerifier::new(inner, vec![crl], RevocationPolicy::RejectIfListed, roots);
+
+    verifier
+        .verify_client_cert(&CertificateDer::from(leaf_der), &[], unix_time_now())
+        .expect_err("a certificate whose serial is listed on an authentic CRL must be rejected");
+}
+
+#[test]
+fn crl_verifier_accepts_non_revoked_serial_on_an_authentic_crl() {
+    // Guards against a false-positive revocation bug: a cert whose serial is *not* on an
+    // otherwise valid, authentically-signed CRL must still be accepted.
+    let serial = [0x2a];
+    let revoked_serial = [0x2b];
+    let (ca_cert, ca_der, leaf_der) = ca_and_leaf("leaf.example", &serial);
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(ca_der))
+        .expect("CA cert should be a valid trust anchor");
+
+    let now = SystemTime::now();
+    let crl_params = CertificateRevocationListParams {
+        this_update: (now - std::time::Duration::from_secs(60)).into(),
+        next_update: (now + std::time::Duration::from_secs(3600)).into(),
+        crl_number: SerialNumber::from_slice(&[0x01]),
+        issuing_distribution_point: None,
+        revoked_certs: vec![RevokedCertParams {
+            serial_number: SerialNumber::from_slice(&revoked_serial),
+            revocation_time: now.into(),
+            reason_code: Some(RevocationReason::KeyCompromise),
+            invalidity_date: None,
+        }],
+        key_identifier_method: KeyIdMethod::Sha256,
+    };
+    let crl_der = crl_params
+        .serialize_der_with_signer(&ca_cert)
+        .expect("serialize CRL signed by the CA");
+    let (_, crl) = x509_parser::revocation_list::CertificateRevocationList::from_der(&crl_der)
+        .expect("parse generated CRL");
+
+    let inner = Verifier::with_default_provider(CertificateMode::AuthorityBased(roots.clone()));
+    let verifier =
+        CrlClientCertVerifier::new(inner, vec![crl], RevocationPolicy::RejectIfListed, roots);
+
+    verifier
+        .verify_client_cert(&CertificateDer::from(leaf_der), &[], unix_time_now())
+        .expect("a certificate whose serial is absent from an authentic CRL must be accepted");
+}
+
+#[test]
+fn crl_verifier_require_crl_for_every_path_rejects_when_none_on_file() {
+    let (_ca_cert, ca_der, leaf_der) = ca_and_leaf("leaf.example", &[0x2a]);
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(ca_der))
+        .expect("CA cert should be a valid trust anchor");
+
+    let inner = Verifier::with_default_provider(CertificateMode::AuthorityBased(roots.clone()));
+    let verifier = CrlClientCertVerifier::new(
+        inner,
+        vec![],
+        RevocationPolicy::RequireCrlForEveryPath,
+        roots,
+    );
+
+    verifier
+        .verify_client_cert(&CertificateDer::from(leaf_der), &[], unix_time_now())
+        .expect_err("RequireCrlForEveryPath must reject a cert whose issuer has no CRL on file");
+}
+
+#[test]
+fn crl_verifier_ignores_an_unsigned_crl_rather_than_trusting_it() {
+    // Regression test for the CRL-authentication fix: a CRL is only trusted once its
+    // signature verifies against a root in the store. Here the CRL is signed by an
+    // *unrelated* key, so it must not be able to revoke a certificate even though the
+    // serial matches and the claimed issuer name matches the real CA.
+    let serial = [0x2a];
+    let (ca_cert, ca_der, leaf_der) = ca_and_leaf("leaf.example", &serial);
+    let (forger_cert, _forger_der, _) = ca_and_leaf("forger.example", &[0x99]);
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(ca_der))
+        .expect("CA cert should be a valid trust anchor");
+
+    let now = SystemTime::now();
+    let crl_params = CertificateRevocationListParams {
+        this_update: (now - std::time::Duration::from_secs(60)).into(),
+        next_update: (now + std::time::Duration::from_secs(3600)).into(),
+        crl_number: SerialNumber::from_slice(&[0x01]),
+        issuing_distribution_point: None,
+        revoked_certs: vec![RevokedCertParams {
+            serial_number: SerialNumber::from_slice(&serial),
+            revocation_time: now.into(),
+            reason_code: Some(RevocationReason::KeyCompromise),
+            invalidity_date: None,
+        }],
+        key_identifier_method: KeyIdMethod::Sha256,
+    };
+    let crl_der = crl_params
+        .serialize_der_with_signer(&forger_cert)
+        .expect("serialize forged CRL");
+    let (_, crl) = x509_parser::revocation_list::CertificateRevocationList::from_der(&crl_der)
+        .expect("parse generated CRL");
+
+    let inner = Verifier::with_default_provider(CertificateMode::AuthorityBased(roots.clone()));
+    let verifier =
+        CrlClientCertVerifier::new(inner, vec![crl], RevocationPolicy::RejectIfListed, roots);
+
+    verifier
+        .verify_client_cert(&CertificateDer::from(leaf_der), &[], unix_time_now())
+        .expect("a CRL that does not verify against the trust anchor must not be able to revoke anything");
+}